@@ -1,34 +1,257 @@
+use std::collections::HashMap;
+use std::collections::VecDeque;
 use std::sync;
 use std::thread;
 use std::time;
 use std::vec;
 
 use crate::builder;
+use crate::identity;
 use futures::executor;
 use futures::future;
 
 // TODO: check usage of &str + lifetime (won't work as listen/ping running thread require 'static lifetime) vs String.
 // TODO: look into caching connections, etc.
-// TODO: look into agents advertising capabilities - OCCI style of course :-)
+
+/// How many round-trip-times we keep around per peer to compute `peer_stats()` from.
+const RTT_HISTORY: usize = 20;
+
+/// Default number of consecutive missed pings before a peer is evicted, see `ping()`.
+pub const CONN_MAX_RETRIES: u32 = 10;
+
+/// How long `add_peer()` waits for the handshake/peer-list round trip before giving up on an
+/// unreachable endpoint, in ms - ZeroMQ's `connect()` is lazy and succeeds even for a dead
+/// peer, so without this a stale or wrong endpoint would block the caller forever.
+const HANDSHAKE_TIMEOUT_MS: i32 = 2_000;
+
+/// Selects the wire transport a `ZeroAgent` runs over - analogous to picking a plain `Tcp`
+/// socket vs. an encrypted one. `Plain` is the default for backward compatibility.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TransportMode {
+    Plain,
+    Encrypted,
+}
 
 /// Type of messages that can be passed between agents.
 pub enum Msg {
     Ping(String),
     Message(String),
     Kill(),
+    /// Connection setup - advertises the sender's own endpoint, network name, protocol version,
+    /// its Curve25519 public key (hex encoded, empty outside `TransportMode::Encrypted`) and the
+    /// capabilities/protocols it supports, so the peer can authenticate and target it.
+    Hand {
+        ep: String,
+        network: String,
+        version: u32,
+        curve_key: String,
+        capabilities: Vec<String>,
+    },
+    /// Reply to a `Hand` - whether the peer was accepted into the swarm.
+    Shake { ok: bool },
+    /// Ask a peer for its current peer list.
+    GetPeers,
+    /// Reply to a `GetPeers` - the peer list of the responder.
+    Peers(Vec<String>),
+    /// Multistream-style protocol negotiation - offers a list of candidate protocols the sender
+    /// would like to speak, in preference order.
+    Offer(Vec<String>),
+    /// Reply to an `Offer` - the first mutually supported protocol, or an empty string if none
+    /// of the candidates are supported.
+    Select(String),
 }
 
 impl Msg {
-    /// Convert a message - ensures format as the listener expects it.
-    pub fn to_msg(&self) -> String {
+    /// The raw, unsigned payload - this is what actually gets signed.
+    fn payload(&self) -> String {
         match &self {
             Msg::Ping(content) => String::from("P@") + content,
             Msg::Message(content) => String::from("M@") + content,
             Msg::Kill() => String::from("K@0"),
+            Msg::Hand {
+                ep,
+                network,
+                version,
+                curve_key,
+                capabilities,
+            } => format!(
+                "H@{},{},{},{},{}",
+                ep,
+                network,
+                version,
+                curve_key,
+                capabilities.join(";")
+            ),
+            Msg::Shake { ok } => format!("S@{}", if *ok { 1 } else { 0 }),
+            Msg::GetPeers => String::from("G@0"),
+            Msg::Peers(peers) => String::from("L@") + &peers.join(","),
+            Msg::Offer(candidates) => String::from("O@") + &candidates.join(","),
+            Msg::Select(proto) => String::from("C@") + proto,
+        }
+    }
+
+    /// Convert a message - ensures format as the listener expects it. Appends the sender's
+    /// public key and a signature over the payload, so the listener can tell the message really
+    /// came from the agent advertising that key.
+    pub fn to_msg(&self, identity: &identity::Identity) -> String {
+        let payload: String = self.payload();
+        let sig: String = identity.sign(&payload);
+        format!("{}#{}#{}", payload, identity.public_key_hex(), sig)
+    }
+}
+
+/// Connection state of a known peer - tracked so a single missed ping doesn't evict it.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PeerConnState {
+    Connected,
+    Retrying(u32),
+    Failed,
+}
+
+/// Bookkeeping kept per known peer - connection state, when we last heard back, recent
+/// round-trip-times so swarm health can be observed via `peer_stats()`, the peer's signing
+/// public key once learned from a `Hand`, its Curve25519 public key (in
+/// `TransportMode::Encrypted`) so we can dial it back as a CURVE client, and the
+/// capabilities/protocols it advertised so callers can target it via `peers_supporting()`.
+#[derive(Clone, Debug)]
+pub struct PeerInfo {
+    pub state: PeerConnState,
+    pub last_seen: Option<time::Instant>,
+    pub rtts: VecDeque<time::Duration>,
+    pub public_key: Option<String>,
+    pub curve_key: Option<String>,
+    pub capabilities: Vec<String>,
+}
+
+impl PeerInfo {
+    pub(crate) fn new() -> Self {
+        Self {
+            state: PeerConnState::Connected,
+            last_seen: None,
+            rtts: VecDeque::with_capacity(RTT_HISTORY),
+            public_key: None,
+            curve_key: None,
+            capabilities: vec![],
+        }
+    }
+
+    /// Record a successful ping - resets the retry counter and remembers the round-trip-time.
+    fn record_rtt(&mut self, rtt: time::Duration) {
+        if self.rtts.len() == RTT_HISTORY {
+            self.rtts.pop_front();
+        }
+        self.rtts.push_back(rtt);
+        self.last_seen = Some(time::Instant::now());
+        self.state = PeerConnState::Connected;
+    }
+
+    /// Record a missed ping - bumps the retry counter, marking the peer `Failed` once
+    /// `max_retries` consecutive misses have happened.
+    fn record_miss(&mut self, max_retries: u32) {
+        let retries: u32 = match self.state {
+            PeerConnState::Retrying(n) => n + 1,
+            _ => 1,
+        };
+        self.state = if retries >= max_retries {
+            PeerConnState::Failed
+        } else {
+            PeerConnState::Retrying(retries)
+        };
+    }
+}
+
+/// Map of peer endpoint to its connection bookkeeping.
+pub type PeerMap = HashMap<String, PeerInfo>;
+
+/// Aggregated round-trip-time stats for a peer, as returned by `peer_stats()`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PeerStats {
+    pub avg: time::Duration,
+    pub median: time::Duration,
+    pub max: time::Duration,
+}
+
+/// FNV-1a 64 bit hash - cheap, dependency-free way to fingerprint a peer list so `Ping` can
+/// carry a handful of bytes instead of the full membership CSV.
+fn fnv1a64(bytes: &[u8]) -> u64 {
+    const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+    const FNV_PRIME: u64 = 0x100000001b3;
+    let mut hash: u64 = FNV_OFFSET;
+    for &byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
+
+/// Hash a peer list - sorted first so the hash doesn't depend on map iteration order.
+fn peers_hash(peers: &[String]) -> u64 {
+    let mut sorted: Vec<&String> = peers.iter().collect();
+    sorted.sort();
+    let joined: String = sorted
+        .iter()
+        .map(|p| p.as_str())
+        .collect::<Vec<&str>>()
+        .join(",");
+    fnv1a64(joined.as_bytes())
+}
+
+/// Split an endpoint as returned by `ZeroAgent::connect_addr()` into the bare address and the
+/// `#` suffixed Curve25519 public key fragment, if any. A `#` suffix that isn't a valid hex
+/// encoded curve key (e.g. in a plain `inproc://#1` endpoint) is left as part of the address.
+fn split_curve_fragment(ep: &str) -> (String, Option<String>) {
+    match ep.rsplit_once('#') {
+        Some((addr, key)) if identity::decode_curve_key(key).is_ok() => {
+            (addr.to_string(), Some(key.to_string()))
         }
+        _ => (ep.to_string(), None),
     }
 }
 
+/// Configure `client` as a CURVE client authenticating the server via `server_curve_key` - a
+/// no-op in `TransportMode::Plain`, and also a no-op if the server's key isn't known yet, in
+/// which case the connection will simply be refused by a CURVE-secured peer.
+fn configure_curve_client(
+    client: &zmq::Socket,
+    mode: TransportMode,
+    curve: &identity::CurveKeys,
+    server_curve_key: Option<&str>,
+) {
+    if mode != TransportMode::Encrypted {
+        return;
+    }
+    if let Some(server_key) = server_curve_key.and_then(|k| identity::decode_curve_key(k).ok()) {
+        client.set_curve_serverkey(&server_key).unwrap();
+        client.set_curve_publickey(curve.public_key()).unwrap();
+        client.set_curve_secretkey(curve.secret_key()).unwrap();
+    }
+}
+
+/// Split a wire string `payload#pubkey_hex#sig_hex` into its three parts. The public key and
+/// signature are hex and never contain `#`, so splitting from the right is unambiguous even if
+/// the payload does (e.g. a `Message` body or an `inproc://#1` endpoint).
+fn split_signed(raw: &str) -> Option<(&str, &str, &str)> {
+    let mut parts = raw.rsplitn(3, '#');
+    let sig: &str = parts.next()?;
+    let pubkey: &str = parts.next()?;
+    let payload: &str = parts.next()?;
+    Some((payload, pubkey, sig))
+}
+
+fn stats_from_rtts(rtts: &VecDeque<time::Duration>) -> Option<PeerStats> {
+    if rtts.is_empty() {
+        return None;
+    }
+    let mut sorted: Vec<time::Duration> = rtts.iter().cloned().collect();
+    sorted.sort();
+    let sum: time::Duration = sorted.iter().sum();
+    Some(PeerStats {
+        avg: sum / sorted.len() as u32,
+        median: sorted[sorted.len() / 2],
+        max: *sorted.last().unwrap(),
+    })
+}
+
 ///
 /// An agent in a multi-agent system-of-systems enabling comms.
 ///
@@ -44,11 +267,18 @@ pub trait Agent {
 ///
 pub struct ZeroAgent {
     pub ep: String,
-    pub peers: sync::Arc<sync::Mutex<vec::Vec<String>>>,
+    pub peers: sync::Arc<sync::Mutex<PeerMap>>,
     pub msgs: sync::Arc<sync::Mutex<vec::Vec<String>>>,
     pub ctxt: zmq::Context,
     pub wait: u64,
     pub timeout: u64,
+    pub network: String,
+    pub version: u32,
+    pub conn_max_retries: u32,
+    pub identity: sync::Arc<identity::Identity>,
+    pub mode: TransportMode,
+    pub curve: sync::Arc<identity::CurveKeys>,
+    pub capabilities: Vec<String>,
 }
 
 ///
@@ -65,150 +295,536 @@ impl ZeroAgent {
         builder::AgentBuilder::new(ep)
     }
 
-    /// add a peer to the multi-agent system.
+    /// This agent's public key, hex encoded - safe to share with peers.
+    pub fn public_key(&self) -> String {
+        self.identity.public_key_hex()
+    }
+
+    /// The endpoint to give other agents' `add_peer()` so they can reach this one. In
+    /// `TransportMode::Encrypted` this carries this agent's Curve25519 public key as a `#`
+    /// suffixed fragment, since a CURVE client has to know the server's key before it can
+    /// connect at all - there is no other bootstrap channel to learn it from.
+    pub fn connect_addr(&self) -> String {
+        match self.mode {
+            TransportMode::Plain => self.ep.clone(),
+            TransportMode::Encrypted => format!("{}#{}", self.ep, self.curve.public_key_hex()),
+        }
+    }
+
+    /// add a peer to the multi-agent system. `ep` is the address as returned by the peer's
+    /// `connect_addr()` - in `TransportMode::Encrypted` that carries the peer's Curve25519
+    /// public key as a `#` suffixed fragment, which is stripped off before connecting and used
+    /// to authenticate it as a CURVE server.
+    ///
+    /// Performs a handshake with the remote peer first: sends our network name and protocol
+    /// version and only joins if the peer shakes back with `ok = true` - this keeps agents
+    /// from accidentally joining an unrelated swarm. On success also asks the peer for its
+    /// current peer list so we bootstrap without waiting for the next ping round.
     pub fn add_peer(&self, ep: String) {
-        let rcp: sync::Arc<sync::Mutex<Vec<String>>> = sync::Arc::clone(&self.peers);
-        let mut peers: sync::MutexGuard<Vec<String>> = rcp.lock().unwrap();
-        if (!peers.contains(&ep)) && ep != self.ep {
-            peers.push(ep);
+        let (ep, peer_curve_key): (String, Option<String>) = split_curve_fragment(&ep);
+        if ep == self.ep {
+            return;
         }
-        drop(peers);
+        let rcp: sync::Arc<sync::Mutex<PeerMap>> = sync::Arc::clone(&self.peers);
+        if rcp.lock().unwrap().contains_key(&ep) {
+            return;
+        }
+
+        let client: zmq::Socket = self.ctxt.socket(zmq::REQ).unwrap();
+        client.set_rcvtimeo(HANDSHAKE_TIMEOUT_MS).unwrap();
+        self.setup_curve_client(&client, peer_curve_key.as_deref());
+        client.connect(&ep).expect("Could not connect to peer");
+
+        client
+            .send(
+                Msg::Hand {
+                    ep: self.ep.clone(),
+                    network: self.network.clone(),
+                    version: self.version,
+                    curve_key: self.handshake_curve_key(),
+                    capabilities: self.capabilities.clone(),
+                }
+                .to_msg(&self.identity)
+                .as_str(),
+                0,
+            )
+            .unwrap();
+        // An unreachable/dead `ep` still connects (ZMQ connect is lazy) but never answers - bail
+        // out without joining rather than blocking the caller forever.
+        let shake: zmq::Message = match client.recv_msg(0) {
+            Ok(shake) => shake,
+            Err(_) => {
+                client.disconnect(&ep).unwrap();
+                return;
+            }
+        };
+        let tmp: String = shake.as_str().unwrap().to_string();
+        let ok: bool = split_signed(&tmp).map(|(payload, _, _)| payload)
+            == Some(Msg::Shake { ok: true }.payload().as_str());
+
+        if ok {
+            let mut peers: sync::MutexGuard<PeerMap> = rcp.lock().unwrap();
+            let info = peers.entry(ep.clone()).or_insert_with(PeerInfo::new);
+            if peer_curve_key.is_some() {
+                info.curve_key = peer_curve_key;
+            }
+            drop(peers);
+
+            // Best-effort bootstrap of the peer's peer list - we've already joined above, so a
+            // timeout here just means we fall back to learning the rest via the next ping round.
+            client
+                .send(Msg::GetPeers.to_msg(&self.identity).as_str(), 0)
+                .unwrap();
+            if let Ok(reply) = client.recv_msg(0) {
+                let tmp: String = reply.as_str().unwrap().to_string();
+                let (payload, _, _) = split_signed(&tmp).unwrap_or((tmp.as_str(), "", ""));
+                let data: Vec<&str> = payload.split('@').collect();
+                if data.len() > 1 && !data[1].is_empty() {
+                    let mut peers: sync::MutexGuard<PeerMap> = rcp.lock().unwrap();
+                    for peer in data[1].split(',') {
+                        if peer != self.ep {
+                            peers.entry(peer.to_string()).or_insert_with(PeerInfo::new);
+                        }
+                    }
+                    drop(peers);
+                }
+            }
+        }
+
+        client.disconnect(&ep).unwrap();
     }
 
-    /// send a message to a particular peer.
+    /// send a message to a particular peer, signed with our identity.
     pub fn send_msg(&self, peer: &str, msg: &Msg) {
         let client = &self.ctxt.socket(zmq::REQ).unwrap();
+        let peer_curve_key: Option<String> = self.known_curve_key(peer);
+        self.setup_curve_client(client, peer_curve_key.as_deref());
         client.connect(peer).expect("Could not connect to peer");
-        client.send(msg.to_msg().as_str(), 0).unwrap();
+        client.send(msg.to_msg(&self.identity).as_str(), 0).unwrap();
         client.recv_msg(0).unwrap(); // Wait for ack...
     }
 
+    /// Like `send_msg()`, but only delivers to `peer` if it advertised `cap` in its `Hand` -
+    /// returns `false` without sending anything otherwise.
+    pub fn send_msg_cap(&self, peer: &str, msg: &Msg, cap: &str) -> bool {
+        if !self.peers_supporting(cap).iter().any(|p| p == peer) {
+            return false;
+        }
+        self.send_msg(peer, msg);
+        true
+    }
+
+    /// The endpoints of known peers that advertised `cap` in their `Hand`. Capability knowledge
+    /// is one-directional: a `Hand` only tells the *receiver* what the *sender* supports, so
+    /// this only reflects peers we learned about by them calling `add_peer()` on us (or that we
+    /// otherwise received a `Hand` from) - peers we added ourselves without them reciprocating
+    /// won't show up here even if they do support `cap`.
+    pub fn peers_supporting(&self, cap: &str) -> Vec<String> {
+        let peers: sync::MutexGuard<PeerMap> = self.peers.lock().unwrap();
+        peers
+            .iter()
+            .filter(|(_, info)| info.capabilities.iter().any(|c| c == cap))
+            .map(|(ep, _)| ep.clone())
+            .collect()
+    }
+
+    /// Like `broadcast()`, but only delivers to peers that advertised `cap`.
+    pub fn broadcast_cap(&self, msg: &str, cap: &str) {
+        for peer in self.peers_supporting(cap) {
+            if peer != self.ep {
+                self.send_msg(&peer, &Msg::Message(msg.to_string()));
+            }
+        }
+    }
+
+    /// Negotiate a protocol with `peer` via a multistream-style `Offer`/`Select` round trip -
+    /// sends our candidates in preference order and returns the first one the peer also
+    /// supports, or `None` if it rejected all of them.
+    pub fn negotiate(&self, peer: &str, candidates: &[String]) -> Option<String> {
+        let client: zmq::Socket = self.ctxt.socket(zmq::REQ).unwrap();
+        let peer_curve_key: Option<String> = self.known_curve_key(peer);
+        self.setup_curve_client(&client, peer_curve_key.as_deref());
+        client.connect(peer).expect("Could not connect to peer");
+        client
+            .send(
+                Msg::Offer(candidates.to_vec())
+                    .to_msg(&self.identity)
+                    .as_str(),
+                0,
+            )
+            .unwrap();
+        let reply: zmq::Message = client.recv_msg(0).unwrap();
+        let tmp: String = reply.as_str().unwrap().to_string();
+        let (payload, _, _) = split_signed(&tmp).unwrap_or((tmp.as_str(), "", ""));
+        client.disconnect(peer).unwrap();
+
+        let data: Vec<&str> = payload.split('@').collect();
+        if data[0] == "C" && !data[1].is_empty() {
+            Some(data[1].to_string())
+        } else {
+            None
+        }
+    }
+
+    /// The Curve25519 public key to advertise in a `Hand`, or an empty string outside
+    /// `TransportMode::Encrypted`.
+    fn handshake_curve_key(&self) -> String {
+        match self.mode {
+            TransportMode::Plain => String::new(),
+            TransportMode::Encrypted => self.curve.public_key_hex(),
+        }
+    }
+
+    /// The curve key we last learned for `peer` via a `Hand` or the `connect_addr()` fragment
+    /// given to `add_peer()`, if any.
+    fn known_curve_key(&self, peer: &str) -> Option<String> {
+        let peers: sync::MutexGuard<PeerMap> = self.peers.lock().unwrap();
+        peers.get(peer).and_then(|info| info.curve_key.clone())
+    }
+
+    /// Configure `client` as a CURVE client using this agent's keypair - see
+    /// `configure_curve_client()`.
+    fn setup_curve_client(&self, client: &zmq::Socket, server_curve_key: Option<&str>) {
+        configure_curve_client(client, self.mode, &self.curve, server_curve_key);
+    }
+
     /// Activate the agents - will start listener and mgmt. threads.
     pub fn activate(&self) -> (thread::JoinHandle<()>, thread::JoinHandle<()>) {
         // The listener threads, watches for incoming messages.
-        let rcp_0: sync::Arc<sync::Mutex<Vec<String>>> = sync::Arc::clone(&self.peers);
+        let rcp_0: sync::Arc<sync::Mutex<PeerMap>> = sync::Arc::clone(&self.peers);
         let msgs: sync::Arc<sync::Mutex<Vec<String>>> = sync::Arc::clone(&self.msgs);
         let ep_0: String = self.ep.clone();
         let ctxt_0: zmq::Context = self.ctxt.clone();
+        let network_0: String = self.network.clone();
+        let version_0: u32 = self.version;
+        let wait_0: u64 = self.wait;
+        let identity_0: sync::Arc<identity::Identity> = sync::Arc::clone(&self.identity);
+        let mode_0: TransportMode = self.mode;
+        let curve_0: sync::Arc<identity::CurveKeys> = sync::Arc::clone(&self.curve);
+        let capabilities_0: Vec<String> = self.capabilities.clone();
         let list_th = thread::spawn(move || {
-            listen(ctxt_0, ep_0, rcp_0, msgs);
+            listen(
+                ctxt_0,
+                ep_0,
+                rcp_0,
+                msgs,
+                network_0,
+                version_0,
+                wait_0,
+                identity_0,
+                mode_0,
+                curve_0,
+                capabilities_0,
+            );
         });
 
         // Ping thread - assures reasonably consistency.
-        let rcp_1: sync::Arc<sync::Mutex<Vec<String>>> = sync::Arc::clone(&self.peers);
+        let rcp_1: sync::Arc<sync::Mutex<PeerMap>> = sync::Arc::clone(&self.peers);
         let ep_1: String = self.ep.clone();
         let ctxt_1: zmq::Context = self.ctxt.clone();
         let timeout_1 = self.timeout;
         let wait_1 = self.wait;
+        let max_retries_1 = self.conn_max_retries;
+        let identity_1: sync::Arc<identity::Identity> = sync::Arc::clone(&self.identity);
+        let mode_1: TransportMode = self.mode;
+        let curve_1: sync::Arc<identity::CurveKeys> = sync::Arc::clone(&self.curve);
         let ping_th = thread::spawn(move || {
-            ping(ctxt_1, ep_1, rcp_1, wait_1, timeout_1);
+            ping(
+                ctxt_1,
+                ep_1,
+                rcp_1,
+                wait_1,
+                timeout_1,
+                max_retries_1,
+                identity_1,
+                mode_1,
+                curve_1,
+            );
         });
         (list_th, ping_th)
     }
 
     pub fn get_n_peers(&self) -> usize {
-        let rcp: sync::Arc<sync::Mutex<Vec<String>>> = sync::Arc::clone(&self.peers);
-        let peers: sync::MutexGuard<Vec<String>> = rcp.lock().unwrap();
+        let rcp: sync::Arc<sync::Mutex<PeerMap>> = sync::Arc::clone(&self.peers);
+        let peers: sync::MutexGuard<PeerMap> = rcp.lock().unwrap();
         let n_peers: usize = peers.len();
         drop(peers);
         n_peers
     }
+
+    /// Returns avg/median/max round-trip-time per peer, so callers can observe swarm health.
+    /// Peers with no recorded pings yet (e.g. ourselves, or a peer just added) are omitted.
+    pub fn peer_stats(&self) -> HashMap<String, PeerStats> {
+        let rcp: sync::Arc<sync::Mutex<PeerMap>> = sync::Arc::clone(&self.peers);
+        let peers: sync::MutexGuard<PeerMap> = rcp.lock().unwrap();
+        let mut stats: HashMap<String, PeerStats> = HashMap::new();
+        for (ep, info) in peers.iter() {
+            if let Some(s) = stats_from_rtts(&info.rtts) {
+                stats.insert(ep.clone(), s);
+            }
+        }
+        drop(peers);
+        stats
+    }
 }
 
-/// Listen to incoming messages and act accordingly.
+/// Listen to incoming messages and act accordingly. `Message` and `Kill` are only honoured if
+/// their signature checks out, so a sender can't forge another agent's key. `Kill` additionally
+/// requires the signer to be either ourselves or a peer whose public key we learned from its
+/// `Hand` - any other sender could otherwise tear the agent down. A validly signed `Message` is
+/// accepted from any signer.
 fn listen(
     ctxt: zmq::Context,
     ep: String,
-    rcp: sync::Arc<sync::Mutex<Vec<String>>>,
+    rcp: sync::Arc<sync::Mutex<PeerMap>>,
     msg_rcp: sync::Arc<sync::Mutex<Vec<String>>>,
+    network: String,
+    version: u32,
+    wait: u64,
+    identity: sync::Arc<identity::Identity>,
+    mode: TransportMode,
+    curve: sync::Arc<identity::CurveKeys>,
+    capabilities: Vec<String>,
 ) {
     let mut done: bool = false;
     let list: zmq::Socket = ctxt.socket(zmq::REP).unwrap();
+    if mode == TransportMode::Encrypted {
+        list.set_curve_server(true).unwrap();
+        list.set_curve_publickey(curve.public_key()).unwrap();
+        list.set_curve_secretkey(curve.secret_key()).unwrap();
+    }
     list.bind(&ep).expect("Could not bind...");
 
     while !done {
         let msg: zmq::Message = list.recv_msg(0).unwrap();
         let tmp: String = msg.as_str().unwrap().to_string();
-        list.send("0", 0).unwrap();
+        let (payload, pubkey, sig) = split_signed(&tmp).unwrap_or((tmp.as_str(), "", ""));
 
-        let split = tmp.split('@');
+        let split = payload.split('@');
         let data = split.collect::<Vec<&str>>();
 
         if data[0] == "P" {
-            let mut peers: sync::MutexGuard<Vec<String>> = rcp.lock().unwrap();
-            for peer in data[1].to_string().split(',') {
-                if !peers.contains(&peer.to_string()) {
-                    peers.push(peer.to_string());
+            let incoming_hash: u64 = data[1].parse().unwrap_or(0);
+            let peers: sync::MutexGuard<PeerMap> = rcp.lock().unwrap();
+            let own_hash: u64 = peers_hash(&peers.keys().cloned().collect::<Vec<String>>());
+            drop(peers);
+
+            if incoming_hash == own_hash {
+                // Nothing changed on our side either - no need to exchange the full list.
+                list.send("0", 0).unwrap();
+            } else {
+                // The sender's peer list differs from ours - ask it for the full list. The
+                // sender only reads our `GetPeers` after its own `thread::sleep(wait)` in
+                // `ping_peer()`, so its `Peers` reply can land anywhere up to ~2 * wait after we
+                // send `GetPeers` - give the follow-up recv that much room rather than racing
+                // `wait` itself, so a slow-but-legitimate reply isn't mistaken for a missing one.
+                list.send(Msg::GetPeers.to_msg(&identity).as_str(), 0).unwrap();
+                list.set_rcvtimeo((2 * wait) as i32).unwrap();
+                let reply = list.recv_msg(0);
+                list.set_rcvtimeo(-1).unwrap();
+                if let Ok(reply) = reply {
+                    let tmp: String = reply.as_str().unwrap().to_string();
+                    let (payload, _, _) = split_signed(&tmp).unwrap_or((tmp.as_str(), "", ""));
+                    let data: Vec<&str> = payload.split('@').collect();
+                    if data[0] == "L" {
+                        let mut peers: sync::MutexGuard<PeerMap> = rcp.lock().unwrap();
+                        if !data[1].is_empty() {
+                            for peer in data[1].split(',') {
+                                peers.entry(peer.to_string()).or_insert_with(PeerInfo::new);
+                            }
+                        }
+                        drop(peers);
+                    }
+                    list.send("0", 0).unwrap();
                 }
+                // else: genuinely timed out - nothing was received, so the REP socket isn't
+                // owed a reply; if the `Peers` frame shows up late anyway it'll be picked up
+                // as a stray top-level "L" below and acked there.
             }
-            drop(peers);
         } else if data[0] == "M" {
-            let mut msgs: sync::MutexGuard<Vec<String>> = msg_rcp.lock().unwrap();
-            msgs.push(String::from(data[1]));
-            drop(msgs);
+            if identity::verify(pubkey, payload, sig) {
+                let mut msgs: sync::MutexGuard<Vec<String>> = msg_rcp.lock().unwrap();
+                msgs.push(String::from(data[1]));
+                drop(msgs);
+            }
+            list.send("0", 0).unwrap();
         } else if data[0] == "K" {
-            let mut peers: sync::MutexGuard<Vec<String>> = rcp.lock().unwrap();
-            peers.clear();
+            let peers: sync::MutexGuard<PeerMap> = rcp.lock().unwrap();
+            let known: bool = pubkey == identity.public_key_hex()
+                || peers.values().any(|info| info.public_key.as_deref() == Some(pubkey));
             drop(peers);
-            done = true;
+            if known && identity::verify(pubkey, payload, sig) {
+                let mut peers: sync::MutexGuard<PeerMap> = rcp.lock().unwrap();
+                peers.clear();
+                drop(peers);
+                done = true;
+            }
+            list.send("0", 0).unwrap();
+        } else if data[0] == "H" {
+            let hand: Vec<&str> = data[1].splitn(5, ',').collect();
+            let peer_ep: &str = hand.first().copied().unwrap_or("");
+            let peer_network: &str = hand.get(1).copied().unwrap_or("");
+            let peer_version: u32 = hand.get(2).and_then(|v| v.parse().ok()).unwrap_or(0);
+            let peer_curve_key: &str = hand.get(3).copied().unwrap_or("");
+            let peer_capabilities: &str = hand.get(4).copied().unwrap_or("");
+            let ok: bool = peer_network == network && peer_version == version;
+            if ok && !peer_ep.is_empty() {
+                let mut peers: sync::MutexGuard<PeerMap> = rcp.lock().unwrap();
+                let info = peers.entry(peer_ep.to_string()).or_insert_with(PeerInfo::new);
+                info.public_key = Some(pubkey.to_string());
+                if !peer_curve_key.is_empty() {
+                    info.curve_key = Some(peer_curve_key.to_string());
+                }
+                info.capabilities = peer_capabilities
+                    .split(';')
+                    .filter(|c| !c.is_empty())
+                    .map(String::from)
+                    .collect();
+                drop(peers);
+            }
+            list.send(Msg::Shake { ok }.to_msg(&identity).as_str(), 0)
+                .unwrap();
+        } else if data[0] == "G" {
+            let peers: sync::MutexGuard<PeerMap> = rcp.lock().unwrap();
+            let known: Vec<String> = peers.keys().cloned().collect();
+            drop(peers);
+            list.send(Msg::Peers(known).to_msg(&identity).as_str(), 0)
+                .unwrap();
+        } else if data[0] == "O" {
+            let candidates: Vec<&str> = if data[1].is_empty() {
+                vec![]
+            } else {
+                data[1].split(',').collect()
+            };
+            let chosen: &str = candidates
+                .iter()
+                .find(|c| capabilities.iter().any(|own| own == *c))
+                .copied()
+                .unwrap_or("");
+            list.send(
+                Msg::Select(chosen.to_string()).to_msg(&identity).as_str(),
+                0,
+            )
+            .unwrap();
+        } else if data[0] == "L" {
+            // A `Peers` reply that missed its `P` branch's follow-up window and arrived as a
+            // fresh top-level request instead - the REQ side still expects an ack, so give it
+            // one even though we no longer do anything with the (by now stale) peer list.
+            list.send("0", 0).unwrap();
         }
     }
 }
 
 ///
-/// Ping an individual peer; will return empty string if all is good, otherwise the URI.
+/// Ping an individual peer; returns the measured round-trip-time, or `None` if the peer did
+/// not ack within `wait`. If the peer's peer-list hash differs from ours it answers with a
+/// `GetPeers` instead of a plain ack - in that case we reply with `my_peers` so it can pick up
+/// the delta, then wait for the final ack.
 ///
 async fn ping_peer(
     ctxt: &zmq::Context,
     peer: &str,
     msg: &Msg,
     wait: u64,
-) -> Result<String, &'static str> {
-    let mut res: String = "".to_string();
+    my_peers: &[String],
+    identity: &identity::Identity,
+    mode: TransportMode,
+    curve: &identity::CurveKeys,
+    peer_curve_key: Option<&str>,
+) -> Result<(String, Option<time::Duration>), &'static str> {
+    let start: time::Instant = time::Instant::now();
 
     let client: zmq::Socket = ctxt.socket(zmq::REQ).unwrap();
     client.set_connect_timeout(2).unwrap();
+    configure_curve_client(&client, mode, curve, peer_curve_key);
     // TODO: would be great to set: ZMQ_REQ_CORRELATE; not support atm.
     client.connect(peer).expect("Could not connect to peer");
-    client.send(msg.to_msg().as_str(), 0).unwrap();
+    client.send(msg.to_msg(identity).as_str(), 0).unwrap();
     thread::sleep(time::Duration::from_millis(wait));
-    if client.recv_msg(zmq::DONTWAIT).is_err() {
-        res = peer.to_string();
-    }
+    let rtt: Option<time::Duration> = match client.recv_msg(zmq::DONTWAIT) {
+        Err(_) => None,
+        Ok(reply)
+            if split_signed(reply.as_str().unwrap_or(""))
+                .map(|(payload, _, _)| payload)
+                == Some(Msg::GetPeers.payload().as_str()) =>
+        {
+            client
+                .send(
+                    Msg::Peers(my_peers.to_vec()).to_msg(identity).as_str(),
+                    0,
+                )
+                .unwrap();
+            thread::sleep(time::Duration::from_millis(wait));
+            if client.recv_msg(zmq::DONTWAIT).is_err() {
+                None
+            } else {
+                Some(start.elapsed())
+            }
+        }
+        Ok(_) => Some(start.elapsed()),
+    };
     client.disconnect(peer).unwrap();
-    Ok(res)
+    Ok((peer.to_string(), rtt))
 }
 
 ///
-/// Will on a given timout try to ping the host it knows and if needed remove peers from the list
-/// of known neighbours.
+/// Will on a given timeout try to ping the hosts it knows, tracking per-peer connection state so
+/// a peer is only removed once it has missed `max_retries` consecutive pings in a row - a single
+/// late reply no longer flaps the swarm membership.
 ///
-/// Could be optimized by only sending delta in data between last msg and new one.
+/// Each `Ping` only carries a hash of our current peer list rather than the full membership CSV -
+/// a peer whose own hash differs pulls the full list via a `GetPeers`/`Peers` round trip, so
+/// steady-state traffic stays O(1) per peer instead of O(N).
 ///
 fn ping(
     ctxt: zmq::Context,
     my_ep: String,
-    rcp: sync::Arc<sync::Mutex<Vec<String>>>,
+    rcp: sync::Arc<sync::Mutex<PeerMap>>,
     wait: u64,
     timeout: u64,
+    max_retries: u32,
+    identity: sync::Arc<identity::Identity>,
+    mode: TransportMode,
+    curve: sync::Arc<identity::CurveKeys>,
 ) {
     loop {
-        let mut peers: sync::MutexGuard<Vec<String>> = rcp.lock().unwrap();
-        let joined = peers.join(",");
-        let msg = Msg::Ping(joined);
+        let mut peers: sync::MutexGuard<PeerMap> = rcp.lock().unwrap();
+        let my_peers: Vec<String> = peers.keys().cloned().collect();
+        let hash: u64 = peers_hash(&my_peers);
+        let msg = Msg::Ping(hash.to_string());
 
         let fut_values: _ = async {
             let mut futures: Vec<_> = vec![];
-            for peer in peers.iter() {
+            for (peer, info) in peers.iter() {
                 if peer != &my_ep {
-                    futures.push(ping_peer(&ctxt, peer, &msg, wait));
+                    futures.push(ping_peer(
+                        &ctxt,
+                        peer,
+                        &msg,
+                        wait,
+                        &my_peers,
+                        &identity,
+                        mode,
+                        &curve,
+                        info.curve_key.as_deref(),
+                    ));
                 }
             }
-            let res: Vec<String> = future::try_join_all(futures).await.unwrap();
+            let res: Vec<(String, Option<time::Duration>)> =
+                future::try_join_all(futures).await.unwrap();
             res
         };
-        let dead_peers: Vec<String> = executor::block_on(fut_values);
-        peers.retain(|x: &String| !dead_peers.contains(x));
+        let results: Vec<(String, Option<time::Duration>)> = executor::block_on(fut_values);
+
+        for (peer, rtt) in results {
+            if let Some(info) = peers.get_mut(&peer) {
+                match rtt {
+                    Some(rtt) => info.record_rtt(rtt),
+                    None => info.record_miss(max_retries),
+                }
+            }
+        }
+        peers.retain(|_, info| info.state != PeerConnState::Failed);
 
         if peers.is_empty() {
             break;
@@ -228,9 +844,9 @@ impl Agent for ZeroAgent {
         res
     }
     fn broadcast(&self, msg: &str) {
-        let rcp: sync::Arc<sync::Mutex<Vec<String>>> = sync::Arc::clone(&self.peers);
-        let peers: sync::MutexGuard<Vec<String>> = rcp.lock().unwrap();
-        for peer in peers.iter() {
+        let rcp: sync::Arc<sync::Mutex<PeerMap>> = sync::Arc::clone(&self.peers);
+        let peers: sync::MutexGuard<PeerMap> = rcp.lock().unwrap();
+        for peer in peers.keys() {
             if peer != &self.ep {
                 self.send_msg(peer, &Msg::Message(msg.to_string()));
             }
@@ -247,13 +863,16 @@ mod tests {
     use crate::agent;
     // Need to bring this in scope so I can use retrieve().
     use crate::agent::Agent;
+    use crate::identity::Identity;
 
-    fn send_kill(ep: &str) {
+    /// Send a `Kill` signed by `identity` - listener only honours it if `identity` is the
+    /// agent's own, or a peer it already knows about.
+    fn send_kill(ep: &str, identity: &Identity) {
         let ctxt = zmq::Context::new();
         let client = ctxt.socket(zmq::REQ).unwrap();
         client.connect(&ep).expect("Could not connect to peer");
         client
-            .send(agent::Msg::Kill().to_msg().as_str(), 0)
+            .send(agent::Msg::Kill().to_msg(identity).as_str(), 0)
             .unwrap();
         client.recv_msg(0).unwrap();
         client.disconnect(&ep).unwrap();
@@ -286,8 +905,8 @@ mod tests {
             &agent::Msg::Message(String::from("hello")),
         );
 
-        send_kill("tcp://127.0.0.1:8787");
-        send_kill("tcp://127.0.0.1:8989");
+        send_kill("tcp://127.0.0.1:8787", &a_0.identity);
+        send_kill("tcp://127.0.0.1:8989", &a_1.identity);
         th0.0.join().unwrap();
         th0.1.join().unwrap();
         th1.0.join().unwrap();
@@ -299,7 +918,7 @@ mod tests {
         let a_0 = agent::ZeroAgent::builder("tcp://127.0.0.1:1234".to_string()).build();
         let ths = a_0.activate();
         thread::sleep(time::Duration::from_millis(2 * a_0.wait));
-        send_kill("tcp://127.0.0.1:1234");
+        send_kill("tcp://127.0.0.1:1234", &a_0.identity);
         ths.0.join().unwrap();
         ths.1.join().unwrap();
     }
@@ -310,7 +929,7 @@ mod tests {
         let ths = a_0.activate();
         a_0.get_n_peers();
         thread::sleep(time::Duration::from_millis(2 * a_0.wait));
-        send_kill("tcp://127.0.0.1:2345");
+        send_kill("tcp://127.0.0.1:2345", &a_0.identity);
         ths.0.join().unwrap();
         ths.1.join().unwrap();
     }
@@ -321,7 +940,7 @@ mod tests {
         let ths = a_0.activate();
         thread::sleep(time::Duration::from_millis(2 * a_0.wait));
         a_0.retrieve();
-        send_kill("tcp://127.0.0.1:9898");
+        send_kill("tcp://127.0.0.1:9898", &a_0.identity);
         ths.0.join().unwrap();
         ths.1.join().unwrap();
     }
@@ -337,18 +956,67 @@ mod tests {
         thread::sleep(time::Duration::from_millis(2 * a_0.wait));
         a_1.broadcast("hello");
 
-        send_kill("tcp://127.0.0.1:3456");
-        send_kill("tcp://127.0.0.1:3457");
+        send_kill("tcp://127.0.0.1:3456", &a_0.identity);
+        send_kill("tcp://127.0.0.1:3457", &a_1.identity);
         th0.0.join().unwrap();
         th0.1.join().unwrap();
         th1.0.join().unwrap();
         th1.1.join().unwrap();
     }
 
+    #[test]
+    fn test_peer_stats_for_success() {
+        let a_0 = agent::ZeroAgent::builder("tcp://127.0.0.1:3458".to_string()).build();
+        a_0.peer_stats();
+    }
+
+    #[test]
+    fn test_peers_supporting_for_success() {
+        let a_0 = agent::ZeroAgent::builder("tcp://127.0.0.1:3459".to_string()).build();
+        a_0.peers_supporting("occi");
+    }
+
+    #[test]
+    fn test_negotiate_for_success() {
+        let a_0 = agent::ZeroAgent::builder("tcp://127.0.0.1:3460".to_string())
+            .capability("occi".to_string())
+            .build();
+        let th0 = a_0.activate();
+        thread::sleep(time::Duration::from_millis(2 * a_0.wait));
+
+        let a_1 = agent::ZeroAgent::builder("tcp://127.0.0.1:3461".to_string()).build();
+        let selected = a_1.negotiate(
+            "tcp://127.0.0.1:3460",
+            &["occi".to_string(), "other".to_string()],
+        );
+        assert_eq!(selected, Some("occi".to_string()));
+
+        send_kill("tcp://127.0.0.1:3460", &a_0.identity);
+        th0.0.join().unwrap();
+        th0.1.join().unwrap();
+    }
+
     // Test for failure.
 
     // TODO: figure this out...
 
+    #[test]
+    fn test_negotiate_for_failure() {
+        let a_0 = agent::ZeroAgent::builder("tcp://127.0.0.1:3462".to_string())
+            .capability("occi".to_string())
+            .build();
+        let th0 = a_0.activate();
+        thread::sleep(time::Duration::from_millis(2 * a_0.wait));
+
+        let a_1 = agent::ZeroAgent::builder("tcp://127.0.0.1:3463".to_string()).build();
+        let selected = a_1.negotiate("tcp://127.0.0.1:3462", &["unsupported".to_string()]);
+        assert_eq!(selected, None);
+
+        send_kill("tcp://127.0.0.1:3462", &a_0.identity);
+        th0.0.join().unwrap();
+        th0.1.join().unwrap();
+    }
+
     // Test for sanity.
 
     #[test]
@@ -370,27 +1038,34 @@ mod tests {
         // a_1 should have received a hello...
         assert_eq!(a_1.msgs.lock().unwrap().to_vec(), vec!["Hello"]);
 
-        send_kill("tcp://127.0.0.1:5000");
-        send_kill("tcp://127.0.0.1:5001");
+        send_kill("tcp://127.0.0.1:5000", &a_0.identity);
+        send_kill("tcp://127.0.0.1:5001", &a_1.identity);
     }
 
     #[test]
     fn test_activate_for_sanity() {
-        let a_0 = agent::ZeroAgent::builder("tcp://127.0.0.1:5002".to_string()).build();
+        let a_0 = agent::ZeroAgent::builder("tcp://127.0.0.1:5002".to_string())
+            .conn_max_retries(1)
+            .build();
         a_0.activate();
         let a_1 = agent::ZeroAgent::builder("tcp://127.0.0.1:5003".to_string()).build();
         a_1.add_peer("tcp://127.0.0.1:5002".to_string());
         a_1.activate();
 
         thread::sleep(time::Duration::from_millis(2 * a_0.wait));
-        send_kill("tcp://127.0.0.1:5003");
+        send_kill("tcp://127.0.0.1:5003", &a_1.identity);
         thread::sleep(time::Duration::from_secs(2 * a_0.timeout));
         // When a_1 is gone, a_0 should only know itself...
         assert_eq!(
-            a_0.peers.lock().unwrap().to_vec(),
+            a_0.peers
+                .lock()
+                .unwrap()
+                .keys()
+                .cloned()
+                .collect::<Vec<String>>(),
             vec!["tcp://127.0.0.1:5002"]
         );
-        send_kill("tcp://127.0.0.1:5002");
+        send_kill("tcp://127.0.0.1:5002", &a_0.identity);
     }
 
     #[test]
@@ -407,8 +1082,8 @@ mod tests {
         assert_eq!(a_0.get_n_peers(), 2);
         assert_eq!(a_1.get_n_peers(), 2);
 
-        send_kill("tcp://127.0.0.1:5004");
-        send_kill("tcp://127.0.0.1:5005");
+        send_kill("tcp://127.0.0.1:5004", &a_0.identity);
+        send_kill("tcp://127.0.0.1:5005", &a_1.identity);
     }
 
     #[test]
@@ -428,8 +1103,8 @@ mod tests {
         let msgs = a_1.retrieve();
         assert_eq!(msgs, vec!["Foo"]);
 
-        send_kill("tcp://127.0.0.1:5006");
-        send_kill("tcp://127.0.0.1:5007");
+        send_kill("tcp://127.0.0.1:5006", &a_0.identity);
+        send_kill("tcp://127.0.0.1:5007", &a_1.identity);
     }
 
     #[test]
@@ -448,7 +1123,115 @@ mod tests {
         let msgs = a_1.retrieve();
         assert_eq!(msgs, vec!["bar"]); // other agent should know...
 
-        send_kill("tcp://127.0.0.1:5008");
-        send_kill("tcp://127.0.0.1:5009");
+        send_kill("tcp://127.0.0.1:5008", &a_0.identity);
+        send_kill("tcp://127.0.0.1:5009", &a_1.identity);
+    }
+
+    #[test]
+    fn test_add_peer_for_sanity() {
+        let a_0 = agent::ZeroAgent::builder("tcp://127.0.0.1:5010".to_string()).build();
+        a_0.activate();
+        let a_1 = agent::ZeroAgent::builder("tcp://127.0.0.1:5011".to_string())
+            .network("other-swarm".to_string())
+            .build();
+        a_1.activate();
+
+        thread::sleep(time::Duration::from_millis(2 * a_0.wait));
+        // a_1 is on a different network - the handshake should be refused.
+        a_1.add_peer(String::from("tcp://127.0.0.1:5010"));
+        assert_eq!(a_1.get_n_peers(), 1);
+
+        send_kill("tcp://127.0.0.1:5010", &a_0.identity);
+        send_kill("tcp://127.0.0.1:5011", &a_1.identity);
+    }
+
+    #[test]
+    fn test_peer_stats_for_sanity() {
+        let a_0 = agent::ZeroAgent::builder("tcp://127.0.0.1:5012".to_string()).build();
+        a_0.activate();
+        let a_1 = agent::ZeroAgent::builder("tcp://127.0.0.1:5013".to_string()).build();
+        a_1.add_peer("tcp://127.0.0.1:5012".to_string());
+        a_1.activate();
+
+        // give the ping loop a couple of rounds to record a round-trip-time.
+        thread::sleep(time::Duration::from_secs(2 * a_0.timeout));
+        let stats = a_0.peer_stats();
+        assert!(stats.contains_key("tcp://127.0.0.1:5013"));
+
+        send_kill("tcp://127.0.0.1:5012", &a_0.identity);
+        send_kill("tcp://127.0.0.1:5013", &a_1.identity);
+    }
+
+    #[test]
+    fn test_peers_supporting_for_sanity() {
+        let a_0 = agent::ZeroAgent::builder("tcp://127.0.0.1:5014".to_string()).build();
+        a_0.activate();
+        let a_1 = agent::ZeroAgent::builder("tcp://127.0.0.1:5015".to_string())
+            .capability("occi".to_string())
+            .build();
+        a_1.add_peer(String::from("tcp://127.0.0.1:5014"));
+        a_1.activate();
+
+        thread::sleep(time::Duration::from_millis(2 * a_0.wait));
+        assert_eq!(
+            a_0.peers_supporting("occi"),
+            vec!["tcp://127.0.0.1:5015".to_string()]
+        );
+        assert!(a_0.peers_supporting("unknown").is_empty());
+
+        send_kill("tcp://127.0.0.1:5014", &a_0.identity);
+        send_kill("tcp://127.0.0.1:5015", &a_1.identity);
+    }
+
+    #[test]
+    fn test_send_msg_cap_for_sanity() {
+        let a_0 = agent::ZeroAgent::builder("tcp://127.0.0.1:5016".to_string()).build();
+        a_0.activate();
+        let a_1 = agent::ZeroAgent::builder("tcp://127.0.0.1:5017".to_string())
+            .capability("occi".to_string())
+            .build();
+        a_1.add_peer(String::from("tcp://127.0.0.1:5016"));
+        a_1.activate();
+
+        thread::sleep(time::Duration::from_millis(2 * a_0.wait));
+        // a_0 knows a_1 advertised "occi" from a_1's Hand, but a_1 never got a Hand from a_0,
+        // so it has no capabilities recorded for a_0 and must not deliver.
+        let delivered = a_1.send_msg_cap(
+            "tcp://127.0.0.1:5016",
+            &agent::Msg::Message(String::from("hi")),
+            "occi",
+        );
+        assert!(!delivered);
+
+        send_kill("tcp://127.0.0.1:5016", &a_0.identity);
+        send_kill("tcp://127.0.0.1:5017", &a_1.identity);
+    }
+
+    #[test]
+    fn test_broadcast_cap_for_sanity() {
+        let a_0 = agent::ZeroAgent::builder("tcp://127.0.0.1:5018".to_string()).build();
+        a_0.activate();
+        let a_1 = agent::ZeroAgent::builder("tcp://127.0.0.1:5019".to_string())
+            .capability("occi".to_string())
+            .build();
+        a_1.add_peer(String::from("tcp://127.0.0.1:5018"));
+        a_1.activate();
+
+        // Capabilities only ever travel in `Hand`, never in a `Ping` - a_1's add_peer() above
+        // sent a_0 a Hand advertising "occi", so a_0 knows a_1 supports it; but a_1 never
+        // received a Hand back from a_0, so a_1's view of a_0 stays empty. Give the ping round
+        // time to settle so that's not conflated with a slow handshake.
+        thread::sleep(time::Duration::from_secs(2 * a_0.timeout));
+
+        // a_1 has no capability info for a_0 at all, so this should not be delivered.
+        a_1.broadcast_cap("hello", "occi");
+        assert!(a_0.retrieve().is_empty());
+
+        // a_0 does have a_1's advertised "occi", so filtering here should actually deliver.
+        a_0.broadcast_cap("hi", "occi");
+        assert_eq!(a_1.retrieve(), vec!["hi"]);
+
+        send_kill("tcp://127.0.0.1:5018", &a_0.identity);
+        send_kill("tcp://127.0.0.1:5019", &a_1.identity);
     }
 }
@@ -1,25 +1,37 @@
-use crate::agent;
+use std::collections::HashMap;
 use std::sync;
 use std::vec;
 
+use crate::agent;
+use crate::agent::PeerInfo;
+use crate::identity;
+
 ///
 /// An struct to help create a new Agent.
 ///
 pub struct AgentBuilder {
     ep: String,
-    peers: sync::Arc<sync::Mutex<Vec<String>>>,
+    peers: sync::Arc<sync::Mutex<agent::PeerMap>>,
     msgs: sync::Arc<sync::Mutex<Vec<String>>>,
     ctxt: zmq::Context,
     wait: u64,
     timeout: u64,
+    network: String,
+    version: u32,
+    conn_max_retries: u32,
+    identity: sync::Arc<identity::Identity>,
+    mode: agent::TransportMode,
+    curve: sync::Arc<identity::CurveKeys>,
+    capabilities: Vec<String>,
 }
 
 /// Builder for creating new agents.
 impl AgentBuilder {
     /// Creates a new agent.
     pub fn new(ep: String) -> Self {
-        let ngbhs: sync::Arc<sync::Mutex<Vec<String>>> =
-            sync::Arc::new(sync::Mutex::new(vec![ep.clone()]));
+        let mut known: agent::PeerMap = HashMap::new();
+        known.insert(ep.clone(), PeerInfo::new());
+        let ngbhs: sync::Arc<sync::Mutex<agent::PeerMap>> = sync::Arc::new(sync::Mutex::new(known));
         let msgs: sync::Arc<sync::Mutex<Vec<String>>> = sync::Arc::new(sync::Mutex::new(vec![]));
         let context: zmq::Context = zmq::Context::new();
         Self {
@@ -29,6 +41,13 @@ impl AgentBuilder {
             msgs,
             wait: 100,
             timeout: 2,
+            network: String::from("default"),
+            version: 1,
+            conn_max_retries: agent::CONN_MAX_RETRIES,
+            identity: sync::Arc::new(identity::Identity::generate()),
+            mode: agent::TransportMode::Plain,
+            curve: sync::Arc::new(identity::CurveKeys::generate()),
+            capabilities: vec![],
         }
     }
 
@@ -44,6 +63,50 @@ impl AgentBuilder {
         self
     }
 
+    /// Set the name of the network this agent is willing to join - peers advertising a
+    /// different network are refused during the handshake.
+    pub fn network(mut self, network: String) -> Self {
+        self.network = network;
+        self
+    }
+
+    /// Set the protocol version this agent speaks - peers advertising a different version
+    /// are refused during the handshake.
+    pub fn version(mut self, version: u32) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Set how many consecutive missed pings a peer may have before it is evicted.
+    pub fn conn_max_retries(mut self, conn_max_retries: u32) -> Self {
+        self.conn_max_retries = conn_max_retries;
+        self
+    }
+
+    /// Pin this agent's identity to a 32 byte seed, given as 64 hex chars or base62 - lets an
+    /// operator keep a stable public key across restarts instead of generating a fresh one
+    /// every time. Without this the agent gets a random identity.
+    pub fn seed(mut self, seed: &str) -> Result<Self, &'static str> {
+        self.identity = sync::Arc::new(identity::Identity::from_seed_str(seed)?);
+        Ok(self)
+    }
+
+    /// Select the wire transport - `Plain` (the default) or `Encrypted`, which runs the
+    /// underlying ZeroMQ sockets as CurveZMQ peers using a freshly generated Curve25519
+    /// keypair.
+    pub fn mode(mut self, mode: agent::TransportMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Advertise another capability/protocol this agent supports - sent to peers in the `Hand`
+    /// handshake so they can target it via `ZeroAgent::peers_supporting()` or negotiate a
+    /// shared protocol via `ZeroAgent::negotiate()`.
+    pub fn capability(mut self, capability: String) -> Self {
+        self.capabilities.push(capability);
+        self
+    }
+
     /// Create the actual ZeroAgent.
     pub fn build(self) -> agent::ZeroAgent {
         let Self {
@@ -53,6 +116,13 @@ impl AgentBuilder {
             msgs,
             wait,
             timeout,
+            network,
+            version,
+            conn_max_retries,
+            identity,
+            mode,
+            curve,
+            capabilities,
         } = self;
         agent::ZeroAgent {
             ep,
@@ -61,6 +131,13 @@ impl AgentBuilder {
             msgs,
             wait,
             timeout,
+            network,
+            version,
+            conn_max_retries,
+            identity,
+            mode,
+            curve,
+            capabilities,
         }
     }
 }
@@ -91,17 +168,103 @@ mod tests {
             .build();
     }
 
+    #[test]
+    fn test_network_for_success() {
+        AgentBuilder::new("inproc://#0".to_string())
+            .network("my-swarm".to_string())
+            .build();
+    }
+
+    #[test]
+    fn test_version_for_success() {
+        AgentBuilder::new("inproc://#0".to_string()).version(2).build();
+    }
+
+    #[test]
+    fn test_conn_max_retries_for_success() {
+        AgentBuilder::new("inproc://#0".to_string())
+            .conn_max_retries(3)
+            .build();
+    }
+
+    #[test]
+    fn test_seed_for_success() {
+        AgentBuilder::new("inproc://#0".to_string())
+            .seed(&"a".repeat(64))
+            .unwrap()
+            .build();
+    }
+
+    #[test]
+    fn test_mode_for_success() {
+        AgentBuilder::new("inproc://#0".to_string())
+            .mode(agent::TransportMode::Encrypted)
+            .build();
+    }
+
+    #[test]
+    fn test_capability_for_success() {
+        AgentBuilder::new("inproc://#0".to_string())
+            .capability("occi".to_string())
+            .build();
+    }
+
     // Test for failure.
 
+    #[test]
+    fn test_seed_for_failure() {
+        assert!(AgentBuilder::new("inproc://#0".to_string())
+            .seed("not-a-valid-seed-!!!")
+            .is_err());
+    }
+
     // Test for sanity.
 
+    #[test]
+    fn test_seed_for_sanity() {
+        let seed: String = "b".repeat(64);
+        let agent: ZeroAgent = AgentBuilder::new("inproc://#0".to_string())
+            .seed(&seed)
+            .unwrap()
+            .build();
+        let other: ZeroAgent = AgentBuilder::new("inproc://#1".to_string())
+            .seed(&seed)
+            .unwrap()
+            .build();
+        assert_eq!(agent.public_key(), other.public_key());
+    }
+
     #[test]
     fn test_build_for_sanity() {
         let agent: ZeroAgent = AgentBuilder::new("inproc://#0".to_string())
             .timeout(1)
             .wait(2)
+            .network("my-swarm".to_string())
+            .version(3)
+            .conn_max_retries(4)
             .build();
         assert_eq!(agent.timeout, 1);
         assert_eq!(agent.wait, 2);
+        assert_eq!(agent.network, "my-swarm");
+        assert_eq!(agent.version, 3);
+        assert_eq!(agent.conn_max_retries, 4);
+    }
+
+    #[test]
+    fn test_mode_for_sanity() {
+        let agent: ZeroAgent = AgentBuilder::new("inproc://#0".to_string())
+            .mode(agent::TransportMode::Encrypted)
+            .build();
+        assert_eq!(agent.mode, agent::TransportMode::Encrypted);
+        assert!(agent.connect_addr().contains('#'));
+    }
+
+    #[test]
+    fn test_capability_for_sanity() {
+        let agent: ZeroAgent = AgentBuilder::new("inproc://#0".to_string())
+            .capability("occi".to_string())
+            .capability("ping".to_string())
+            .build();
+        assert_eq!(agent.capabilities, vec!["occi", "ping"]);
     }
 }
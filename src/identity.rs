@@ -0,0 +1,227 @@
+use ed25519_dalek::Signer;
+use ed25519_dalek::Verifier;
+
+const BASE62_ALPHABET: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+///
+/// An Ed25519 keypair identifying an agent on the wire - used to sign `Msg`s so peers can tell
+/// a message really came from the agent advertising a given public key.
+///
+pub struct Identity {
+    signing_key: ed25519_dalek::SigningKey,
+}
+
+impl Identity {
+    /// Generate a fresh, random identity.
+    pub fn generate() -> Self {
+        let mut csprng = rand::rngs::OsRng;
+        Self {
+            signing_key: ed25519_dalek::SigningKey::generate(&mut csprng),
+        }
+    }
+
+    /// Parse an identity from a 32 byte seed given as hex or base62.
+    pub fn from_seed_str(seed: &str) -> Result<Self, &'static str> {
+        let bytes: Vec<u8> = decode_seed(seed)?;
+        if bytes.len() != 32 {
+            return Err("seed must decode to exactly 32 bytes");
+        }
+        let mut arr: [u8; 32] = [0; 32];
+        arr.copy_from_slice(&bytes);
+        Ok(Self {
+            signing_key: ed25519_dalek::SigningKey::from_bytes(&arr),
+        })
+    }
+
+    /// This identity's public key, hex encoded - safe to advertise to peers.
+    pub fn public_key_hex(&self) -> String {
+        encode_hex(self.signing_key.verifying_key().as_bytes())
+    }
+
+    /// Sign a payload, returning the hex encoded signature.
+    pub fn sign(&self, payload: &str) -> String {
+        encode_hex(&self.signing_key.sign(payload.as_bytes()).to_bytes())
+    }
+}
+
+/// Verify that `payload` was signed by the holder of `public_key_hex`, producing `sig_hex`.
+pub fn verify(public_key_hex: &str, payload: &str, sig_hex: &str) -> bool {
+    let key_bytes: Vec<u8> = match decode_hex(public_key_hex) {
+        Ok(b) if b.len() == 32 => b,
+        _ => return false,
+    };
+    let sig_bytes: Vec<u8> = match decode_hex(sig_hex) {
+        Ok(b) if b.len() == 64 => b,
+        _ => return false,
+    };
+    let mut key_arr: [u8; 32] = [0; 32];
+    key_arr.copy_from_slice(&key_bytes);
+    let mut sig_arr: [u8; 64] = [0; 64];
+    sig_arr.copy_from_slice(&sig_bytes);
+
+    let key: ed25519_dalek::VerifyingKey = match ed25519_dalek::VerifyingKey::from_bytes(&key_arr)
+    {
+        Ok(k) => k,
+        Err(_) => return false,
+    };
+    let sig: ed25519_dalek::Signature = ed25519_dalek::Signature::from_bytes(&sig_arr);
+    key.verify(payload.as_bytes(), &sig).is_ok()
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn decode_hex(s: &str) -> Result<Vec<u8>, &'static str> {
+    if s.len() % 2 != 0 {
+        return Err("hex string must have an even length");
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).map_err(|_| "invalid hex digit"))
+        .collect()
+}
+
+fn decode_base62(s: &str) -> Result<Vec<u8>, &'static str> {
+    let mut bytes: Vec<u8> = vec![0];
+    for c in s.chars() {
+        let digit: u32 = BASE62_ALPHABET
+            .iter()
+            .position(|&b| b == c as u8)
+            .ok_or("invalid base62 character")? as u32;
+        let mut carry: u32 = digit;
+        for byte in bytes.iter_mut().rev() {
+            let value: u32 = (*byte as u32) * 62 + carry;
+            *byte = (value & 0xff) as u8;
+            carry = value >> 8;
+        }
+        while carry > 0 {
+            bytes.insert(0, (carry & 0xff) as u8);
+            carry >>= 8;
+        }
+    }
+    if bytes.len() > 32 {
+        return Err("seed too large");
+    }
+    let mut padded: Vec<u8> = vec![0; 32 - bytes.len()];
+    padded.extend(bytes);
+    Ok(padded)
+}
+
+/// Seeds are accepted as 64 hex chars, otherwise treated as base62.
+fn decode_seed(seed: &str) -> Result<Vec<u8>, &'static str> {
+    if seed.len() == 64 && seed.chars().all(|c| c.is_ascii_hexdigit()) {
+        decode_hex(seed)
+    } else {
+        decode_base62(seed)
+    }
+}
+
+///
+/// A Curve25519 keypair used to run the ZeroMQ transport in CurveZMQ's encrypted mode - see
+/// `agent::TransportMode::Encrypted`. Kept separate from `Identity`'s Ed25519 signing key since
+/// CurveZMQ needs its own key type.
+///
+pub struct CurveKeys {
+    public_key: [u8; 32],
+    secret_key: [u8; 32],
+}
+
+impl CurveKeys {
+    /// Generate a fresh, random Curve25519 keypair.
+    pub fn generate() -> Self {
+        let pair: zmq::CurveKeyPair =
+            zmq::CurveKeyPair::new().expect("could not generate a curve25519 keypair");
+        Self {
+            public_key: pair.public_key,
+            secret_key: pair.secret_key,
+        }
+    }
+
+    /// This keypair's public key, hex encoded - safe to share with peers.
+    pub fn public_key_hex(&self) -> String {
+        encode_hex(&self.public_key)
+    }
+
+    /// The raw public key bytes, as expected by `zmq::Socket::set_curve_publickey()`.
+    pub fn public_key(&self) -> &[u8; 32] {
+        &self.public_key
+    }
+
+    /// The raw secret key bytes, as expected by `zmq::Socket::set_curve_secretkey()`.
+    pub fn secret_key(&self) -> &[u8; 32] {
+        &self.secret_key
+    }
+}
+
+/// Decode a hex encoded Curve25519 public key, as carried by `Msg::Hand`, into raw bytes
+/// suitable for `zmq::Socket::set_curve_serverkey()`.
+pub fn decode_curve_key(key_hex: &str) -> Result<[u8; 32], &'static str> {
+    let bytes: Vec<u8> = decode_hex(key_hex)?;
+    if bytes.len() != 32 {
+        return Err("curve key must decode to exactly 32 bytes");
+    }
+    let mut arr: [u8; 32] = [0; 32];
+    arr.copy_from_slice(&bytes);
+    Ok(arr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Test for success.
+
+    #[test]
+    fn test_generate_for_success() {
+        Identity::generate();
+    }
+
+    #[test]
+    fn test_sign_for_success() {
+        let id = Identity::generate();
+        id.sign("hello");
+    }
+
+    #[test]
+    fn test_curve_keys_generate_for_success() {
+        CurveKeys::generate();
+    }
+
+    // Test for failure.
+
+    #[test]
+    fn test_from_seed_str_for_failure() {
+        assert!(Identity::from_seed_str("not-a-valid-seed-!!!").is_err());
+    }
+
+    #[test]
+    fn test_decode_curve_key_for_failure() {
+        assert!(decode_curve_key("not-a-valid-key-!!!").is_err());
+    }
+
+    // Test for sanity.
+
+    #[test]
+    fn test_sign_and_verify_for_sanity() {
+        let id = Identity::generate();
+        let sig = id.sign("hello world");
+        assert!(verify(&id.public_key_hex(), "hello world", &sig));
+        assert!(!verify(&id.public_key_hex(), "tampered", &sig));
+    }
+
+    #[test]
+    fn test_from_seed_str_for_sanity() {
+        let seed = "a".repeat(64);
+        let id_0 = Identity::from_seed_str(&seed).unwrap();
+        let id_1 = Identity::from_seed_str(&seed).unwrap();
+        assert_eq!(id_0.public_key_hex(), id_1.public_key_hex());
+    }
+
+    #[test]
+    fn test_decode_curve_key_for_sanity() {
+        let keys = CurveKeys::generate();
+        let decoded = decode_curve_key(&keys.public_key_hex()).unwrap();
+        assert_eq!(&decoded, keys.public_key());
+    }
+}
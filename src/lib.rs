@@ -5,3 +5,6 @@ pub mod agent;
 
 /// The builder for the agent.
 pub mod builder;
+
+/// Ed25519 agent identities used to sign and verify messages.
+pub mod identity;